@@ -0,0 +1,152 @@
+//! Implementation of [`FrameAllocator`], which allocates and deallocates
+//! physical frames.
+//!
+//! Frames are reference counted: copy-on-write sharing (see `MemorySet`)
+//! bumps a frame's count instead of copying it, and only the owner that
+//! drops the count to zero actually returns the frame to the free list.
+use super::{PhysAddr, PhysPageNum};
+use crate::config::MEMORY_END;
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Formatter};
+use lazy_static::*;
+
+/// Manages a frame which has the same lifecycle as the tracker.
+pub struct FrameTracker {
+    pub ppn: PhysPageNum,
+}
+
+impl FrameTracker {
+    pub fn new(ppn: PhysPageNum) -> Self {
+        // page cleaning
+        let bytes_array = ppn.get_bytes_array();
+        for byte in bytes_array {
+            *byte = 0;
+        }
+        frame_add_ref(ppn);
+        Self { ppn }
+    }
+}
+
+impl Debug for FrameTracker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("FrameTracker:PPN={:#x}", self.ppn.0))
+    }
+}
+
+impl Drop for FrameTracker {
+    fn drop(&mut self) {
+        frame_dealloc(self.ppn);
+    }
+}
+
+trait FrameAllocator {
+    fn new() -> Self;
+    fn alloc(&mut self) -> Option<PhysPageNum>;
+    fn dealloc(&mut self, ppn: PhysPageNum);
+}
+
+pub struct StackFrameAllocator {
+    current: usize,
+    end: usize,
+    recycled: Vec<usize>,
+}
+
+impl StackFrameAllocator {
+    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.current = l.0;
+        self.end = r.0;
+    }
+}
+impl FrameAllocator for StackFrameAllocator {
+    fn new() -> Self {
+        Self {
+            current: 0,
+            end: 0,
+            recycled: Vec::new(),
+        }
+    }
+    fn alloc(&mut self) -> Option<PhysPageNum> {
+        if let Some(ppn) = self.recycled.pop() {
+            Some(ppn.into())
+        } else if self.current == self.end {
+            None
+        } else {
+            self.current += 1;
+            Some((self.current - 1).into())
+        }
+    }
+    fn dealloc(&mut self, ppn: PhysPageNum) {
+        let ppn = ppn.0;
+        // validity check
+        if ppn >= self.current || self.recycled.iter().any(|v| *v == ppn) {
+            panic!("Frame ppn={:#x} has not been allocated!", ppn);
+        }
+        // recycle
+        self.recycled.push(ppn);
+    }
+}
+
+type FrameAllocatorImpl = StackFrameAllocator;
+
+lazy_static! {
+    pub static ref FRAME_ALLOCATOR: UPSafeCell<FrameAllocatorImpl> =
+        unsafe { UPSafeCell::new(FrameAllocatorImpl::new()) };
+    /// Reference count per physical frame, used for copy-on-write sharing.
+    /// A frame absent from the map (or mapped to 0) is not currently shared.
+    static ref FRAME_REF_COUNTS: UPSafeCell<BTreeMap<usize, usize>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+pub fn init_frame_allocator() {
+    extern "C" {
+        fn ekernel();
+    }
+    FRAME_ALLOCATOR.exclusive_access().init(
+        PhysAddr::from(ekernel as usize).ceil(),
+        PhysAddr::from(MEMORY_END).floor(),
+    );
+}
+
+pub fn frame_alloc() -> Option<FrameTracker> {
+    FRAME_ALLOCATOR
+        .exclusive_access()
+        .alloc()
+        .map(FrameTracker::new)
+}
+
+pub fn frame_dealloc(ppn: PhysPageNum) {
+    let should_free = {
+        let mut counts = FRAME_REF_COUNTS.exclusive_access();
+        match counts.get_mut(&ppn.0) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            Some(_) => {
+                counts.remove(&ppn.0);
+                true
+            }
+            None => true,
+        }
+    };
+    if should_free {
+        FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
+    }
+}
+
+/// Record that one more address space now references `ppn` (copy-on-write
+/// fork sharing a frame between parent and child).
+pub fn frame_add_ref(ppn: PhysPageNum) {
+    let mut counts = FRAME_REF_COUNTS.exclusive_access();
+    *counts.entry(ppn.0).or_insert(0) += 1;
+}
+
+/// How many address spaces currently reference `ppn`. A frame that was
+/// never shared (or has already fallen back to exclusive ownership) reports
+/// 1, matching the "just restore the write bit" fast path of the COW fault
+/// handler.
+pub fn frame_ref_count(ppn: PhysPageNum) -> usize {
+    *FRAME_REF_COUNTS.exclusive_access().get(&ppn.0).unwrap_or(&1)
+}
@@ -0,0 +1,542 @@
+//! Implementation of [`MapArea`] and [`MemorySet`].
+use super::{
+    frame_alloc, frame_add_ref, frame_ref_count, FrameTracker, PTEFlags, PageTable,
+    PageTableEntry, PhysAddr, PhysPageNum, VPNRange, VirtAddr, VirtPageNum,
+};
+use crate::config::{MEMORY_END, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::*;
+use core::arch::asm;
+use lazy_static::*;
+use riscv::register::satp;
+
+extern "C" {
+    fn stext();
+    fn etext();
+    fn srodata();
+    fn erodata();
+    fn sdata();
+    fn edata();
+    fn sbss_with_stack();
+    fn ebss();
+    fn ekernel();
+    fn strampoline();
+}
+
+lazy_static! {
+    /// a memory set instance through lazy_static! managing kernel space
+    pub static ref KERNEL_SPACE: Arc<UPSafeCell<MemorySet>> =
+        Arc::new(unsafe { UPSafeCell::new(MemorySet::new_kernel()) });
+}
+
+/// How a `MapArea`'s pages are backed: `Identical` maps each virtual page
+/// onto the physical page of the same number (used for the kernel's own
+/// address space), `Framed` gives each page a freshly allocated frame.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MapType {
+    Identical,
+    Framed,
+}
+
+bitflags! {
+    pub struct MapPermission: u8 {
+        const V = 1 << 0;
+        const R = 1 << 1;
+        const W = 1 << 2;
+        const X = 1 << 3;
+        const U = 1 << 4;
+    }
+}
+
+pub struct MapArea {
+    pub vpn_range: VPNRange,
+    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    map_type: MapType,
+    pub map_perm: MapPermission,
+    /// Whether this area is shared copy-on-write between a forked parent
+    /// and child. While `cow` is set, every mapped page is deliberately
+    /// read-only even though `map_perm` may include `W`; a store fault on
+    /// one of these pages is handled by `trap::handle_cow_page_fault`.
+    pub cow: bool,
+}
+
+impl MapArea {
+    pub fn new(
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_type: MapType,
+        map_perm: MapPermission,
+    ) -> Self {
+        let start_vpn: VirtPageNum = start_va.floor();
+        let end_vpn: VirtPageNum = end_va.ceil();
+        Self {
+            vpn_range: VPNRange::new(start_vpn, end_vpn),
+            data_frames: BTreeMap::new(),
+            map_type,
+            map_perm,
+            cow: false,
+        }
+    }
+
+    /// Build a new area over the same range as `other`, for copy-on-write
+    /// fork: the two areas will end up pointing at the same physical
+    /// frames, both mapped read-only, both flagged `cow`.
+    pub fn from_cow(other: &MapArea) -> Self {
+        Self {
+            vpn_range: other.vpn_range,
+            data_frames: BTreeMap::new(),
+            map_type: other.map_type,
+            map_perm: other.map_perm,
+            cow: true,
+        }
+    }
+
+    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        let ppn: PhysPageNum;
+        match self.map_type {
+            MapType::Identical => {
+                ppn = PhysPageNum(vpn.0);
+            }
+            MapType::Framed => {
+                let frame = frame_alloc().unwrap();
+                ppn = frame.ppn;
+                self.data_frames.insert(vpn, frame);
+            }
+        }
+        let mut pte_flags = PTEFlags::from_bits(self.map_perm.bits()).unwrap();
+        if self.cow {
+            // writable bit is withheld until the COW fault handler decides
+            // whether the frame still needs copying
+            pte_flags.remove(PTEFlags::W);
+        }
+        page_table.map(vpn, ppn, pte_flags);
+    }
+
+    /// Map `vpn` read-only onto the *same* frame `frame` already used by
+    /// another address space (copy-on-write share), recording it as owned
+    /// by this area too. The caller must already have bumped the frame's
+    /// refcount for this new owner.
+    pub fn map_cow_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum, frame: FrameTracker) {
+        let ppn = frame.ppn;
+        self.data_frames.insert(vpn, frame);
+        let mut pte_flags = PTEFlags::from_bits(self.map_perm.bits()).unwrap();
+        pte_flags.remove(PTEFlags::W);
+        page_table.map(vpn, ppn, pte_flags);
+    }
+
+    pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        if self.map_type == MapType::Framed {
+            self.data_frames.remove(&vpn);
+        }
+        page_table.unmap(vpn);
+    }
+    pub fn map(&mut self, page_table: &mut PageTable) {
+        for vpn in self.vpn_range {
+            self.map_one(page_table, vpn);
+        }
+    }
+    pub fn unmap(&mut self, page_table: &mut PageTable) {
+        for vpn in self.vpn_range {
+            self.unmap_one(page_table, vpn);
+        }
+    }
+    /// Shrink the mapped range to `new_end`, unmapping everything after it.
+    pub fn shrink_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        for vpn in VPNRange::new(new_end, self.vpn_range.get_end()) {
+            self.unmap_one(page_table, vpn);
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+    }
+    /// data: start-aligned but maybe with shorter length assume that all
+    /// frames were cleared before
+    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) {
+        assert_eq!(self.map_type, MapType::Framed);
+        let mut start: usize = 0;
+        let mut current_vpn = self.vpn_range.get_start();
+        let len = data.len();
+        loop {
+            let src = &data[start..len.min(start + PAGE_SIZE)];
+            let dst = &mut page_table
+                .translate(current_vpn)
+                .unwrap()
+                .ppn()
+                .get_bytes_array()[..src.len()];
+            dst.copy_from_slice(src);
+            start += PAGE_SIZE;
+            if start >= len {
+                break;
+            }
+            current_vpn.0 += 1;
+        }
+    }
+    /// The physical frame currently backing `vpn` in this area, if any.
+    pub fn frame_tracker(&self, vpn: VirtPageNum) -> Option<&FrameTracker> {
+        self.data_frames.get(&vpn)
+    }
+    /// Carve `[start, end)` out of this (about-to-be-discarded) area into a
+    /// fresh `MapArea`, moving over whichever frames in that sub-range it
+    /// already owns. The carved-out pages stay mapped in the page table
+    /// exactly as they were; only bookkeeping ownership moves. Used by
+    /// `MemorySet::munmap` to split an area that is only partially
+    /// unmapped.
+    pub fn extract_subrange(&mut self, start: VirtPageNum, end: VirtPageNum) -> MapArea {
+        let mut new_area = MapArea {
+            vpn_range: VPNRange::new(start, end),
+            data_frames: BTreeMap::new(),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            cow: self.cow,
+        };
+        for vpn in VPNRange::new(start, end) {
+            if let Some(frame) = self.data_frames.remove(&vpn) {
+                new_area.data_frames.insert(vpn, frame);
+            }
+        }
+        new_area
+    }
+    /// Take over ownership of `frame` as the backing for `vpn`, dropping
+    /// whatever frame (typically a COW-shared one) this area previously
+    /// held there. Used by the store-page-fault handler once it has copied
+    /// a shared page into a private frame.
+    pub fn replace_frame(&mut self, vpn: VirtPageNum, frame: FrameTracker) {
+        self.data_frames.insert(vpn, frame);
+    }
+}
+
+pub struct MemorySet {
+    pub page_table: PageTable,
+    pub areas: Vec<MapArea>,
+}
+
+impl MemorySet {
+    pub fn new_bare() -> Self {
+        Self {
+            page_table: PageTable::new(),
+            areas: Vec::new(),
+        }
+    }
+    pub fn token(&self) -> usize {
+        self.page_table.token()
+    }
+    /// Map `[start_va, end_va)` as a fresh framed area, failing with `-1`
+    /// if any page in that range is already mapped by an existing area —
+    /// regions in a `MemorySet` must never overlap.
+    pub fn insert_framed_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+    ) -> isize {
+        if self.range_is_mapped(start_va.floor(), end_va.ceil()) {
+            return -1;
+        }
+        self.push(MapArea::new(start_va, end_va, MapType::Framed, permission), None);
+        0
+    }
+    /// Whether any page in `[start_vpn, end_vpn)` is already mapped by an
+    /// existing area, used to reject overlapping `mmap` requests.
+    fn range_is_mapped(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        self.areas.iter().any(|area| {
+            let a_start = area.vpn_range.get_start().0;
+            let a_end = area.vpn_range.get_end().0;
+            start_vpn.0 < a_end && a_start < end_vpn.0
+        })
+    }
+    /// Whether every page in `[start_vpn, end_vpn)` is currently mapped by
+    /// some area, the precondition `munmap` enforces before unmapping.
+    fn range_is_fully_mapped(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        for vpn in VPNRange::new(start_vpn, end_vpn) {
+            if !self.areas.iter().any(|area| {
+                area.vpn_range.get_start().0 <= vpn.0 && vpn.0 < area.vpn_range.get_end().0
+            }) {
+                return false;
+            }
+        }
+        true
+    }
+    /// Unmap every page in `[start_vpn, end_vpn)`, failing with `false`
+    /// unless every page in that range is currently mapped. Areas that are
+    /// only partially covered are split or truncated rather than removed
+    /// outright, so the remaining mapping keeps its original permissions.
+    pub fn munmap(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        if !self.range_is_fully_mapped(start_vpn, end_vpn) {
+            return false;
+        }
+        let mut split_off = Vec::new();
+        let mut idx = 0;
+        while idx < self.areas.len() {
+            let a_start = self.areas[idx].vpn_range.get_start();
+            let a_end = self.areas[idx].vpn_range.get_end();
+            if end_vpn.0 <= a_start.0 || a_end.0 <= start_vpn.0 {
+                // no overlap with the range being unmapped
+                idx += 1;
+                continue;
+            }
+            let mut area = self.areas.remove(idx);
+            let inter_start = VirtPageNum(start_vpn.0.max(a_start.0));
+            let inter_end = VirtPageNum(end_vpn.0.min(a_end.0));
+            for vpn in VPNRange::new(inter_start, inter_end) {
+                area.unmap_one(&mut self.page_table, vpn);
+            }
+            // left remainder, before the unmapped range, keeps its own frames
+            if a_start.0 < inter_start.0 {
+                split_off.push(area.extract_subrange(a_start, inter_start));
+            }
+            // right remainder, after the unmapped range, keeps its own frames
+            if inter_end.0 < a_end.0 {
+                split_off.push(area.extract_subrange(inter_end, a_end));
+            }
+            // `area` itself covered exactly `[inter_start, inter_end)` (or
+            // less) and has nothing left to own; it is simply dropped
+            // idx now indexes the area that followed the one we removed
+        }
+        self.areas.extend(split_off);
+        true
+    }
+    pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
+        if let Some((idx, area)) = self
+            .areas
+            .iter_mut()
+            .enumerate()
+            .find(|(_, area)| area.vpn_range.get_start() == start_vpn)
+        {
+            area.unmap(&mut self.page_table);
+            self.areas.remove(idx);
+        }
+    }
+    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
+        map_area.map(&mut self.page_table);
+        if let Some(data) = data {
+            map_area.copy_data(&mut self.page_table, data);
+        }
+        self.areas.push(map_area);
+    }
+    /// Mention that trampoline is not collected by areas.
+    fn map_trampoline(&mut self) {
+        extern "C" {
+            fn strampoline();
+        }
+        self.page_table.map(
+            VirtAddr::from(TRAMPOLINE).into(),
+            PhysAddr::from(strampoline as usize).into(),
+            PTEFlags::R | PTEFlags::X,
+        );
+    }
+    /// Without kernel stacks.
+    pub fn new_kernel() -> Self {
+        let mut memory_set = Self::new_bare();
+        memory_set.map_trampoline();
+        // map kernel sections
+        memory_set.push(
+            MapArea::new(
+                (stext as usize).into(),
+                (etext as usize).into(),
+                MapType::Identical,
+                MapPermission::R | MapPermission::X,
+            ),
+            None,
+        );
+        memory_set.push(
+            MapArea::new(
+                (srodata as usize).into(),
+                (erodata as usize).into(),
+                MapType::Identical,
+                MapPermission::R,
+            ),
+            None,
+        );
+        memory_set.push(
+            MapArea::new(
+                (sdata as usize).into(),
+                (edata as usize).into(),
+                MapType::Identical,
+                MapPermission::R | MapPermission::W,
+            ),
+            None,
+        );
+        memory_set.push(
+            MapArea::new(
+                (sbss_with_stack as usize).into(),
+                (ebss as usize).into(),
+                MapType::Identical,
+                MapPermission::R | MapPermission::W,
+            ),
+            None,
+        );
+        memory_set.push(
+            MapArea::new(
+                (ekernel as usize).into(),
+                MEMORY_END.into(),
+                MapType::Identical,
+                MapPermission::R | MapPermission::W,
+            ),
+            None,
+        );
+        memory_set
+    }
+    /// Include sections in elf and trampoline and TrapContext and user stack,
+    /// also returns user_sp and entry point.
+    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+        let mut memory_set = Self::new_bare();
+        memory_set.map_trampoline();
+        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+        let elf_header = elf.header;
+        let magic = elf_header.pt1.magic;
+        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
+        let ph_count = elf_header.pt2.ph_count();
+        let mut max_end_vpn = VirtPageNum(0);
+        for i in 0..ph_count {
+            let ph = elf.program_header(i).unwrap();
+            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
+                let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
+                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
+                let mut map_perm = MapPermission::U;
+                let ph_flags = ph.flags();
+                if ph_flags.is_read() {
+                    map_perm |= MapPermission::R;
+                }
+                if ph_flags.is_write() {
+                    map_perm |= MapPermission::W;
+                }
+                if ph_flags.is_execute() {
+                    map_perm |= MapPermission::X;
+                }
+                let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
+                max_end_vpn = map_area.vpn_range.get_end();
+                memory_set.push(
+                    map_area,
+                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
+                );
+            }
+        }
+        // map user stack with U flags
+        let max_end_va: VirtAddr = max_end_vpn.into();
+        let mut user_stack_bottom: usize = max_end_va.into();
+        // guard page
+        user_stack_bottom += PAGE_SIZE;
+        let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
+        memory_set.push(
+            MapArea::new(
+                user_stack_bottom.into(),
+                user_stack_top.into(),
+                MapType::Framed,
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            ),
+            None,
+        );
+        // map TrapContext
+        memory_set.push(
+            MapArea::new(
+                TRAP_CONTEXT.into(),
+                TRAMPOLINE.into(),
+                MapType::Framed,
+                MapPermission::R | MapPermission::W,
+            ),
+            None,
+        );
+        (
+            memory_set,
+            user_stack_top,
+            elf.header.pt2.entry_point() as usize,
+        )
+    }
+    /// Copy-on-write clone of an existing user address space, used by
+    /// `fork`. Rather than copying every data frame, each framed area is
+    /// re-mapped read-only in both the parent and the child, sharing the
+    /// same physical frames with a bumped refcount; the actual copy only
+    /// happens lazily in the store-page-fault handler.
+    ///
+    /// The trap context area is the one exception: `fork` immediately
+    /// writes into the child's trap context (to fix up `kernel_sp`), and
+    /// `__alltraps` unconditionally writes into it on every trap. Sharing
+    /// it COW would let that write clobber the parent's trap context and
+    /// fault on the read-only page, so it is eagerly copied into a
+    /// private, always-writable area instead.
+    pub fn from_existing_user(user_space: &mut MemorySet) -> MemorySet {
+        let mut memory_set = Self::new_bare();
+        memory_set.map_trampoline();
+        let trap_context_vpn = VirtAddr::from(TRAP_CONTEXT).floor();
+        for area in user_space.areas.iter_mut() {
+            if area.vpn_range.get_start() == trap_context_vpn {
+                let mut new_area = MapArea::new(
+                    area.vpn_range.get_start().into(),
+                    area.vpn_range.get_end().into(),
+                    MapType::Framed,
+                    area.map_perm,
+                );
+                new_area.map(&mut memory_set.page_table);
+                for vpn in area.vpn_range {
+                    let src = user_space
+                        .page_table
+                        .translate(vpn)
+                        .unwrap()
+                        .ppn()
+                        .get_bytes_array();
+                    let dst = memory_set
+                        .page_table
+                        .translate(vpn)
+                        .unwrap()
+                        .ppn()
+                        .get_bytes_array();
+                    dst.copy_from_slice(src);
+                }
+                memory_set.areas.push(new_area);
+                continue;
+            }
+            let mut new_area = MapArea::from_cow(area);
+            // the parent's own copy of this area is now shared too: mark it
+            // `cow` so the parent's own writes go through
+            // `handle_cow_page_fault` instead of faulting straight to
+            // "core dumped"
+            area.cow = true;
+            for vpn in area.vpn_range {
+                if let Some(parent_pte) = user_space.page_table.find_pte(vpn) {
+                    if !parent_pte.is_valid() {
+                        continue;
+                    }
+                    let ppn = parent_pte.ppn();
+                    if let Some(frame) = area.frame_tracker(vpn) {
+                        // share the frame: bump the refcount and point the
+                        // child's PTE at it too
+                        frame_add_ref(ppn);
+                        new_area.map_cow_one(&mut memory_set.page_table, vpn, FrameTracker { ppn });
+                    }
+                    // parent's page must also become read-only now that it
+                    // is shared
+                    parent_pte.clear_write();
+                } else {
+                    continue;
+                }
+            }
+            memory_set.areas.push(new_area);
+        }
+        // the parent keeps running on this page table; without a flush the
+        // hart's TLB can still hold the old writable entries we just
+        // cleared above, letting it silently keep writing shared frames
+        unsafe {
+            asm!("sfence.vma");
+        }
+        memory_set
+    }
+    pub fn activate(&self) {
+        let satp = self.page_table.token();
+        unsafe {
+            satp::write(satp);
+            asm!("sfence.vma");
+        }
+    }
+    pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
+        self.page_table.translate(vpn)
+    }
+    pub fn recycle_data_pages(&mut self) {
+        self.areas.clear();
+    }
+    /// Moving the program break is not supported: this always returns
+    /// `None` and leaves the address space unchanged, whatever `_size` is.
+    pub fn change_program_brk(&mut self, _size: i32) -> Option<usize> {
+        None
+    }
+}
@@ -0,0 +1,24 @@
+//! Memory management implementation
+//!
+//! SV39 page-based virtual-memory management. Every task's program memory
+//! is an independent [`MemorySet`] holding multiple [`MapArea`]s, one
+//! contiguous virtual-memory range each.
+mod address;
+mod frame_allocator;
+mod memory_set;
+mod page_table;
+
+use address::VPNRange;
+pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+pub use frame_allocator::{frame_add_ref, frame_alloc, frame_dealloc, frame_ref_count, FrameTracker};
+pub use memory_set::{MapArea, MapPermission, MapType, MemorySet, KERNEL_SPACE};
+pub use page_table::{
+    copy_to_user, translated_byte_buffer, translated_refmut, translated_str, PTEFlags, PageTable,
+    PageTableEntry,
+};
+
+/// Initialize heap allocator, frame allocator and kernel space
+pub fn init() {
+    frame_allocator::init_frame_allocator();
+    KERNEL_SPACE.exclusive_access().activate();
+}
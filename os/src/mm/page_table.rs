@@ -0,0 +1,261 @@
+//! Implementation of [`PageTableEntry`] and [`PageTable`].
+use super::{frame_alloc, FrameTracker, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use bitflags::*;
+
+bitflags! {
+    /// Page Table Entry flags
+    pub struct PTEFlags: u8 {
+        const V = 1 << 0;
+        const R = 1 << 1;
+        const W = 1 << 2;
+        const X = 1 << 3;
+        const U = 1 << 4;
+        const G = 1 << 5;
+        const A = 1 << 6;
+        const D = 1 << 7;
+    }
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct PageTableEntry {
+    pub bits: usize,
+}
+
+impl PageTableEntry {
+    pub fn new(ppn: PhysPageNum, flags: PTEFlags) -> Self {
+        Self {
+            bits: ppn.0 << 10 | flags.bits as usize,
+        }
+    }
+    pub fn empty() -> Self {
+        Self { bits: 0 }
+    }
+    pub fn ppn(&self) -> PhysPageNum {
+        (self.bits >> 10 & ((1usize << 44) - 1)).into()
+    }
+    pub fn flags(&self) -> PTEFlags {
+        PTEFlags::from_bits(self.bits as u8).unwrap()
+    }
+    pub fn is_valid(&self) -> bool {
+        (self.flags() & PTEFlags::V) != PTEFlags::empty()
+    }
+    pub fn readable(&self) -> bool {
+        (self.flags() & PTEFlags::R) != PTEFlags::empty()
+    }
+    pub fn writable(&self) -> bool {
+        (self.flags() & PTEFlags::W) != PTEFlags::empty()
+    }
+    pub fn executable(&self) -> bool {
+        (self.flags() & PTEFlags::X) != PTEFlags::empty()
+    }
+    /// Clear the writable bit, used to mark a copy-on-write page read-only
+    /// in both the parent's and child's page tables.
+    pub fn clear_write(&mut self) {
+        let flags = self.flags() & !PTEFlags::W;
+        self.bits = self.ppn().0 << 10 | flags.bits as usize;
+    }
+    /// Restore the writable bit in place, used by the COW fault handler
+    /// when the faulting frame is no longer shared.
+    pub fn set_write(&mut self) {
+        let flags = self.flags() | PTEFlags::W;
+        self.bits = self.ppn().0 << 10 | flags.bits as usize;
+    }
+    /// Repoint this entry at a freshly copied frame, used by the COW fault
+    /// handler when the frame is still shared with another address space.
+    pub fn set_ppn(&mut self, ppn: PhysPageNum) {
+        let flags = self.flags();
+        self.bits = ppn.0 << 10 | flags.bits as usize;
+    }
+}
+
+/// Sv39 page table, holding the frames for all levels except the leaves,
+/// which are tracked by the owning `MemorySet`'s `MapArea`s.
+pub struct PageTable {
+    root_ppn: PhysPageNum,
+    frames: Vec<FrameTracker>,
+}
+
+impl PageTable {
+    pub fn new() -> Self {
+        let frame = frame_alloc().unwrap();
+        PageTable {
+            root_ppn: frame.ppn,
+            frames: vec![frame],
+        }
+    }
+    /// Temporarily used to get arguments from user space.
+    pub fn from_token(satp: usize) -> Self {
+        Self {
+            root_ppn: PhysPageNum::from(satp & ((1usize << 44) - 1)),
+            frames: Vec::new(),
+        }
+    }
+    fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        let idxs = vpn.indexes();
+        let mut ppn = self.root_ppn;
+        let mut result: Option<&mut PageTableEntry> = None;
+        for (i, idx) in idxs.iter().enumerate() {
+            let pte = &mut ppn.get_pte_array()[*idx];
+            if i == 2 {
+                result = Some(pte);
+                break;
+            }
+            if !pte.is_valid() {
+                let frame = frame_alloc().unwrap();
+                *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+                self.frames.push(frame);
+            }
+            ppn = pte.ppn();
+        }
+        result
+    }
+    pub fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        let idxs = vpn.indexes();
+        let mut ppn = self.root_ppn;
+        let mut result: Option<&mut PageTableEntry> = None;
+        for (i, idx) in idxs.iter().enumerate() {
+            let pte = &mut ppn.get_pte_array()[*idx];
+            if i == 2 {
+                result = Some(pte);
+                break;
+            }
+            if !pte.is_valid() {
+                return None;
+            }
+            ppn = pte.ppn();
+        }
+        result
+    }
+    #[allow(unused)]
+    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+    }
+    #[allow(unused)]
+    pub fn unmap(&mut self, vpn: VirtPageNum) {
+        let pte = self.find_pte(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
+        *pte = PageTableEntry::empty();
+    }
+    pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
+        self.find_pte(vpn).map(|pte| *pte)
+    }
+    pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
+        self.find_pte(va.clone().floor()).map(|pte| {
+            let aligned_pa: PhysAddr = pte.ppn().into();
+            let offset = va.page_offset();
+            (aligned_pa.0 + offset).into()
+        })
+    }
+    pub fn token(&self) -> usize {
+        8usize << 60 | self.root_ppn.0
+    }
+}
+
+/// Translate a pointer to `u8` array through page table and return a
+/// series of `&'static mut u8` slices (crossing page boundaries).
+///
+/// The caller may write through these slices (e.g. `sys_read` filling a
+/// user buffer), so every page touched has its copy-on-write sharing
+/// broken first — a kernel-side `write_volatile` never takes a page fault
+/// to drive the normal COW path.
+pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
+    let page_table = PageTable::from_token(token);
+    let mut start = ptr as usize;
+    let end = start + len;
+    let mut v = Vec::new();
+    while start < end {
+        let start_va = VirtAddr::from(start);
+        let mut vpn = start_va.floor();
+        crate::task::ensure_cow_writable(vpn);
+        let ppn = page_table.translate(vpn).unwrap().ppn();
+        vpn.0 += 1;
+        let mut end_va: VirtAddr = vpn.into();
+        end_va = end_va.min(VirtAddr::from(end));
+        if end_va.page_offset() == 0 {
+            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..]);
+        } else {
+            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
+        }
+        start = end_va.into();
+    }
+    v
+}
+
+pub fn translated_str(token: usize, ptr: *const u8) -> String {
+    let page_table = PageTable::from_token(token);
+    let mut string = String::new();
+    let mut va = ptr as usize;
+    loop {
+        let ch: u8 = *(page_table
+            .translate_va(VirtAddr::from(va))
+            .unwrap()
+            .get_mut());
+        if ch == 0 {
+            break;
+        }
+        string.push(ch as char);
+        va += 1;
+    }
+    string
+}
+
+/// Resolve a user pointer to a kernel-writable reference. Breaks
+/// copy-on-write sharing on the page first, since the write that follows
+/// goes straight through `write_volatile`-free memory access and so would
+/// otherwise silently mutate a frame still shared with another process.
+pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
+    let page_table = PageTable::from_token(token);
+    let va = ptr as usize;
+    crate::task::ensure_cow_writable(VirtAddr::from(va).floor());
+    page_table
+        .translate_va(VirtAddr::from(va))
+        .unwrap()
+        .get_mut()
+}
+
+/// Copy `src` into the user address space at `dst_va`, crossing page
+/// boundaries correctly.
+///
+/// This is the write-side counterpart to [`translated_byte_buffer`]: the
+/// destination byte range is walked page by page through the user
+/// [`PageTable`], `src` is split at each page boundary, and each fragment
+/// is `write_volatile`'d into the right physical frame. Syscalls that copy
+/// a kernel-built struct out to a user pointer (`sys_get_time`,
+/// `sys_task_info`, ...) should go through this rather than resolving a
+/// single page and writing the whole struct through it, which corrupts
+/// memory whenever the struct straddles a page boundary.
+///
+/// Each page written also has its copy-on-write sharing broken first, for
+/// the same reason as `translated_byte_buffer`/`translated_refmut`: this
+/// `write_volatile` never takes a page fault to drive the normal COW path.
+pub fn copy_to_user(token: usize, dst_va: usize, src: &[u8]) {
+    let page_table = PageTable::from_token(token);
+    let mut start = dst_va;
+    let end = dst_va + src.len();
+    let mut copied = 0;
+    while start < end {
+        let start_va = VirtAddr::from(start);
+        let mut vpn = start_va.floor();
+        crate::task::ensure_cow_writable(vpn);
+        let ppn = page_table.translate(vpn).unwrap().ppn();
+        vpn.0 += 1;
+        let mut end_va: VirtAddr = vpn.into();
+        end_va = end_va.min(VirtAddr::from(end));
+        let frag_len = usize::from(end_va) - start;
+        let page_bytes = ppn.get_bytes_array();
+        let page_off = start_va.page_offset();
+        for i in 0..frag_len {
+            unsafe {
+                (&mut page_bytes[page_off + i] as *mut u8).write_volatile(src[copied + i]);
+            }
+        }
+        copied += frag_len;
+        start = end_va.into();
+    }
+}
@@ -3,13 +3,15 @@
 use alloc::sync::Arc;
 
 use crate::{
-    config::{MAX_SYSCALL_NUM, PAGE_SIZE_BITS},
+    config::MAX_SYSCALL_NUM,
+    fs::{open_file, OpenFlags},
     loader::get_app_data_by_name,
-    mm::{translated_refmut, translated_str, PageTable, VirtAddr},
+    mm::{copy_to_user, translated_refmut, translated_str, VirtAddr},
     task::{
-        add_task, current_task, current_user_token, exit_current_and_run_next,
-        get_current_task_info, get_current_task_status, insert_framed_area,
-        suspend_current_and_run_next, un_map, TaskStatus,
+        add_task, all_tasks, current_task, current_user_token, exit_current_and_run_next,
+        get_current_task_info, get_current_task_status, insert_framed_area, kill_task,
+        munmap_range, set_current_task_priority, set_current_task_signal_action, sigreturn,
+        suspend_current_and_run_next, SignalAction, TaskStatus,
     },
     timer::get_time_us,
 };
@@ -120,61 +122,46 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
     // ---- release current PCB automatically
 }
 
-/// YOUR JOB: get time with second and microsecond
-/// HINT: You might reimplement it with virtual memory management.
-/// HINT: What if [`TimeVal`] is splitted by two pages ?
+/// Serialize `val` to its raw bytes and copy it into the user address
+/// space at `ptr`, one page at a time, so callers are correct regardless
+/// of whether `T` happens to straddle a page boundary.
+fn copy_struct_to_user<T>(ptr: *mut T, val: &T) {
+    let bytes = unsafe {
+        core::slice::from_raw_parts(val as *const T as *const u8, core::mem::size_of::<T>())
+    };
+    copy_to_user(current_user_token(), ptr as usize, bytes);
+}
+
+/// get time with second and microsecond
 pub fn sys_get_time(ts: *mut TimeVal, _tz: usize) -> isize {
-    let ts_pa = get_pa_from_va(ts as usize) as *mut TimeVal;
     let us = get_time_us();
-    unsafe {
-        *ts_pa = TimeVal {
-            sec: us / 1000000,
-            usec: us % 1000000,
-        };
-    }
+    let time_val = TimeVal {
+        sec: us / 1000000,
+        usec: us % 1000000,
+    };
+    copy_struct_to_user(ts, &time_val);
     0
 }
 
-/// use a virtual addr to get it's mapped physic addr
-pub fn get_pa_from_va(va: usize) -> usize {
-    let current_user_token = current_user_token();
-    let current_page_table = PageTable::from_token(current_user_token);
-    let vpn = VirtAddr::from(va).floor();
-    let vpn_offset = VirtAddr::from(va).page_offset();
-    let ppn = current_page_table.translate(vpn).unwrap().ppn().0;
-    let pa = ppn << PAGE_SIZE_BITS | vpn_offset;
-    pa
-}
-/// YOUR JOB: Finish sys_task_info to pass testcases
-/// HINT: You might reimplement it with virtual memory management.
-/// HINT: What if [`TaskInfo`] is splitted by two pages ?
+/// Report the calling task's status and per-syscall counters.
 pub fn sys_task_info(ti: *mut TaskInfo) -> isize {
-    unsafe {
-        let ti_pa = get_pa_from_va(ti as usize) as *mut TaskInfo;
-        // current task status
-        let t_task_status = get_current_task_status();
-        if t_task_status.is_none() {
-            return -1;
-        } else {
-            (*ti_pa).status = t_task_status.unwrap();
-        }
-        // task syscalls times
-        let t_task_info = get_current_task_info();
-        if t_task_info.is_none() {
-            return -1;
-        } else {
-            let info = t_task_info.unwrap();
-            (*ti_pa).syscall_times = info.syscall_times;
-            (*ti_pa).time = info.time;
-            (*ti_pa).status = t_task_status.unwrap();
-        }
-    }
+    let Some(status) = get_current_task_status() else {
+        return -1;
+    };
+    let Some(info) = get_current_task_info() else {
+        return -1;
+    };
+    let task_info = TaskInfo {
+        status,
+        syscall_times: info.syscall_times,
+        time: info.time,
+    };
+    copy_struct_to_user(ti, &task_info);
     0
 }
 
 /// YOUR JOB: Implement mmap.
 pub fn sys_mmap(start: usize, len: usize, mut port: usize) -> isize {
-    println!("start :{} len:{} port:{} ", start, len, port);
     if len == 0 {
         return -1;
     }
@@ -199,24 +186,20 @@ pub fn sys_mmap(start: usize, len: usize, mut port: usize) -> isize {
     // avalable
     port |= 0x1;
 
-    println!("port value ======> {:b}", port);
-    let result = insert_framed_area(VirtAddr::from(start), VirtAddr::from(start + len), port);
-    println!("00000000000 return {}", result);
-    return result;
+    insert_framed_area(VirtAddr::from(start), VirtAddr::from(start + len), port)
 }
 
-/// YOUR JOB: Implement munmap.
+/// Unmap `[start, start + len)`, failing with -1 unless every page in that
+/// range is currently mapped. Areas only partially covered by the range
+/// are split rather than rejected, so e.g. unmapping the middle third of a
+/// larger `mmap`'d region leaves the two outer thirds mapped.
 pub fn sys_munmap(start: usize, len: usize) -> isize {
     if start & 0xfff != 0 {
         return -1;
     }
-    let start_vpn = VirtAddr::from(start).floor().0;
-    let end_vpn = VirtAddr::from(start + len).ceil().0;
-    let mut result = 0;
-    for vpn in start_vpn..end_vpn {
-        result = un_map(vpn.into());
-    }
-    result
+    let start_vpn = VirtAddr::from(start).floor();
+    let end_vpn = VirtAddr::from(start + len).ceil();
+    munmap_range(start_vpn, end_vpn)
 }
 
 /// change data segment size
@@ -229,21 +212,144 @@ pub fn sys_sbrk(size: i32) -> isize {
     }
 }
 
-/// YOUR JOB: Implement spawn.
-/// HINT: fork + exec =/= spawn
-pub fn sys_spawn(_path: *const u8) -> isize {
+/// Spawn a new process directly from the named ELF file.
+///
+/// This is deliberately not `fork` + `exec`: we never duplicate the
+/// caller's address space just to throw it away. The new process is built
+/// straight from the ELF image and linked in as a child of the caller.
+pub fn sys_spawn(path: *const u8) -> isize {
+    trace!("kernel:pid[{}] sys_spawn", current_task().unwrap().pid.0);
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
+        let elf_data = app_inode.read_all();
+        let current_task = current_task().unwrap();
+        let new_task = current_task.spawn(elf_data.as_slice());
+        let new_pid = new_task.pid.0;
+        add_task(new_task);
+        new_pid as isize
+    } else {
+        -1
+    }
+}
+
+/// Set the priority of the current process; the scheduler uses this via the
+/// stride algorithm (smallest `pass` runs next, advanced by
+/// `BIG_STRIDE / priority` each time).
+pub fn sys_set_priority(prio: isize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_set_priority {}",
+        current_task().unwrap().pid.0,
+        prio
+    );
+    match set_current_task_priority(prio) {
+        Some(prio) => prio,
+        None => -1,
+    }
+}
+
+/// Set signal `signum` pending on process `pid`. Fails with `-1` if
+/// `signum` is out of range or `pid` does not name a live task.
+pub fn sys_kill(pid: usize, signum: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_kill pid:{} signum:{}",
+        current_task().unwrap().pid.0,
+        pid,
+        signum
+    );
+    kill_task(pid, signum)
+}
+
+/// Install a handler for `signum`, writing the previously installed one
+/// out to `old_action`. `action`/`old_action` may each be null, in which
+/// case that side is skipped. Fails with `-1` if `signum` is out of range
+/// or is `SIGKILL`, which can never be caught.
+pub fn sys_sigaction(
+    signum: usize,
+    action: *const SignalAction,
+    old_action: *mut SignalAction,
+) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_sigaction signum:{}",
+        current_task().unwrap().pid.0,
+        signum
+    );
+    let token = current_user_token();
+    let new_action = if action.is_null() {
+        None
+    } else {
+        Some(*translated_refmut(token, action as *mut SignalAction))
+    };
+    match set_current_task_signal_action(signum, new_action) {
+        Some(old) => {
+            if !old_action.is_null() {
+                *translated_refmut(token, old_action) = old;
+            }
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Restore the trap context a signal handler interrupted, called by the
+/// handler's trampoline once it has finished running.
+pub fn sys_sigreturn() -> isize {
     trace!(
-        "kernel:pid[{}] sys_spawn NOT IMPLEMENTED",
+        "kernel:pid[{}] sys_sigreturn",
         current_task().unwrap().pid.0
     );
-    -1
+    sigreturn()
+}
+
+/// Invocation count and cumulative microseconds spent in one syscall, as
+/// surfaced by `sys_proc_info`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SyscallStat {
+    /// Number of times the syscall has been invoked
+    pub count: u32,
+    /// Cumulative wall-clock microseconds spent inside it
+    pub total_us: usize,
 }
 
-// YOUR JOB: Set task priority.
-pub fn sys_set_priority(_prio: isize) -> isize {
+/// One row of the table `sys_proc_info` fills in: a live task's pid,
+/// scheduling priority, status, and per-syscall stats, enough for a
+/// user-space monitor to render a top-like view.
+#[repr(C)]
+pub struct ProcInfo {
+    pub pid: usize,
+    pub priority: isize,
+    pub status: TaskStatus,
+    pub syscall_stats: [SyscallStat; MAX_SYSCALL_NUM],
+}
+
+/// Copy out up to `len` [`ProcInfo`] rows, one per live task, into `buf`.
+/// Returns the number of rows written.
+pub fn sys_proc_info(buf: *mut ProcInfo, len: usize) -> isize {
     trace!(
-        "kernel:pid[{}] sys_set_priority NOT IMPLEMENTED",
+        "kernel:pid[{}] sys_proc_info",
         current_task().unwrap().pid.0
     );
-    -1
+    let tasks = all_tasks();
+    let count = tasks.len().min(len);
+    for (i, task) in tasks.iter().take(count).enumerate() {
+        let inner = task.inner_exclusive_access();
+        let mut syscall_stats = [SyscallStat {
+            count: 0,
+            total_us: 0,
+        }; MAX_SYSCALL_NUM];
+        for (id, stat) in syscall_stats.iter_mut().enumerate() {
+            stat.count = inner.task_info.syscall_times[id];
+            stat.total_us = inner.task_info.syscall_total_us[id];
+        }
+        let entry = ProcInfo {
+            pid: task.getpid(),
+            priority: inner.priority,
+            status: inner.task_status,
+            syscall_stats,
+        };
+        drop(inner);
+        copy_struct_to_user(unsafe { buf.add(i) }, &entry);
+    }
+    count as isize
 }
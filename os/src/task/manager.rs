@@ -0,0 +1,101 @@
+//! Implementation of [`TaskManager`]
+//!
+//! The ready queue is scheduled with the **stride algorithm**: every task
+//! carries a `pass` counter that is advanced by its own `stride` each time it
+//! is scheduled, and `fetch_task` always hands out the task with the
+//! smallest `pass`. A smaller `stride` (i.e. a higher priority) means a task
+//! advances more slowly and therefore gets picked more often.
+use super::TaskControlBlock;
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+pub struct TaskManager {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+/// A stride scheduler over the ready queue; FIFO order only breaks ties.
+impl TaskManager {
+    pub fn new() -> Self {
+        Self {
+            ready_queue: VecDeque::new(),
+        }
+    }
+    /// Add process back to ready queue
+    pub fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+    /// Take a process out of the ready queue: the one with the smallest
+    /// `pass` wins; on a tie the one that has been waiting longest (earlier
+    /// in the queue) wins. The winner's `pass` is then advanced by its
+    /// `stride` for the next round.
+    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        if self.ready_queue.is_empty() {
+            return None;
+        }
+        let mut best = 0;
+        let mut best_pass = self.ready_queue[0].inner_exclusive_access().pass;
+        for (idx, task) in self.ready_queue.iter().enumerate().skip(1) {
+            let pass = task.inner_exclusive_access().pass;
+            // `pass.wrapping_sub(best_pass) as i64 < 0` means `pass` precedes
+            // `best_pass`, which stays correct as long as no stride exceeds
+            // half of the `u64` range (guaranteed by `priority >= MIN_PRIORITY`).
+            if (pass.wrapping_sub(best_pass) as i64) < 0 {
+                best = idx;
+                best_pass = pass;
+            }
+        }
+        let task = self.ready_queue.remove(best).unwrap();
+        let mut inner = task.inner_exclusive_access();
+        let stride = inner.stride;
+        inner.pass = inner.pass.wrapping_add(stride);
+        drop(inner);
+        Some(task)
+    }
+}
+
+lazy_static! {
+    pub static ref TASK_MANAGER: UPSafeCell<TaskManager> =
+        unsafe { UPSafeCell::new(TaskManager::new()) };
+    /// Registry of every live task by pid, independent of the ready queue
+    /// (a task currently running, or blocked waiting on a child, is absent
+    /// from `ready_queue` but must still be reachable by `sys_kill` and
+    /// `sys_proc_info`).
+    pub static ref PID2TCB: UPSafeCell<BTreeMap<usize, Arc<TaskControlBlock>>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+/// Add process back to ready queue
+pub fn add_task(task: Arc<TaskControlBlock>) {
+    TASK_MANAGER.exclusive_access().add(task);
+}
+
+/// Take a process out of the ready queue, selected by the stride algorithm
+pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
+    TASK_MANAGER.exclusive_access().fetch()
+}
+
+/// Register a newly created task in the pid registry, called once by
+/// `add_initproc` (for the init process) and by `fork`/`spawn`.
+pub fn insert_into_pid2task(pid: usize, task: Arc<TaskControlBlock>) {
+    PID2TCB.exclusive_access().insert(pid, task);
+}
+
+/// Drop a task from the pid registry, called once it has exited.
+pub fn remove_from_pid2task(pid: usize) {
+    PID2TCB.exclusive_access().remove(&pid);
+}
+
+/// Look a live task up by pid, used by `sys_kill`.
+pub fn pid2task(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    PID2TCB.exclusive_access().get(&pid).cloned()
+}
+
+/// Every live task, in pid order, used by `sys_proc_info` to build its
+/// top-like table.
+pub fn all_tasks() -> Vec<Arc<TaskControlBlock>> {
+    PID2TCB.exclusive_access().values().cloned().collect()
+}
@@ -17,6 +17,7 @@ mod context;
 mod id;
 mod manager;
 mod processor;
+mod signal;
 mod switch;
 #[allow(clippy::module_inception)]
 #[allow(rustdoc::private_intra_doc_links)]
@@ -32,12 +33,13 @@ use alloc::sync::Arc;
 pub use context::TaskContext;
 use lazy_static::*;
 pub use manager::{fetch_task, TaskManager};
+pub use signal::{SignalAction, MAX_SIG, SIGKILL};
 use switch::__switch;
 use task::TaskInfo;
 pub use task::{TaskControlBlock, TaskStatus};
 
 pub use id::{kstack_alloc, pid_alloc, KernelStack, PidHandle};
-pub use manager::add_task;
+pub use manager::{add_task, all_tasks, pid2task, remove_from_pid2task};
 pub use processor::{
     current_task, current_trap_cx, current_user_token, run_tasks, schedule, take_current_task,
     Processor,
@@ -112,6 +114,7 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     inner.fd_table.clear();
     drop(inner);
     // **** release current PCB
+    remove_from_pid2task(pid);
     // drop task manually to maintain rc correctly
     drop(task);
     // we do not have to save task context
@@ -133,6 +136,7 @@ lazy_static! {
 
 ///Add init process to the manager
 pub fn add_initproc() {
+    manager::insert_into_pid2task(INITPROC.getpid(), INITPROC.clone());
     add_task(INITPROC.clone());
 }
 
@@ -191,6 +195,23 @@ pub fn set_current_task_info(syscall_id: usize) {
     }
 }
 
+/// Record one invocation of `syscall_id` on the current task, in addition
+/// to the bookkeeping `set_current_task_info` already does: called once
+/// at dispatch with the wall-clock microseconds the syscall took, sampled
+/// by the caller via `get_time_us` at entry and exit.
+///
+/// This, via `set_current_task_info`, is the *only* place `syscall_times`
+/// is incremented — `trap_handler` is the sole caller, once per syscall.
+/// Do not add another increment site (e.g. in a future syscall dispatcher)
+/// without removing the call here, or `sys_task_info`/`sys_proc_info` will
+/// double-count.
+pub fn record_current_task_syscall_time(syscall_id: usize, elapsed_us: usize) {
+    set_current_task_info(syscall_id);
+    if let Some(tcb) = current_task() {
+        tcb.inner_exclusive_access().task_info.syscall_total_us[syscall_id] += elapsed_us;
+    }
+}
+
 /// insert freamd page area from virtaddr range
 pub fn insert_framed_area(start_va: VirtAddr, end_va: VirtAddr, prot: usize) -> isize {
     if let Some(tcb) = current_task() {
@@ -203,23 +224,182 @@ pub fn insert_framed_area(start_va: VirtAddr, end_va: VirtAddr, prot: usize) ->
     }
 }
 
-/// unmap a page
-pub fn un_map(vpn: VirtPageNum) -> isize {
+/// Set the priority of the current task and recompute its stride.
+///
+/// Returns the new priority on success, or `None` if `prio` is below
+/// [`task::MIN_PRIORITY`].
+pub fn set_current_task_priority(prio: isize) -> Option<isize> {
+    if prio < task::MIN_PRIORITY {
+        return None;
+    }
+    let current_task = current_task()?;
+    let mut inner = current_task.inner_exclusive_access();
+    inner.priority = prio;
+    inner.update_stride();
+    Some(prio)
+}
+
+/// Unmap every page in `[start_vpn, end_vpn)`, splitting or truncating
+/// whichever areas only partially overlap the range. Fails with `-1`
+/// unless every page in the range is currently mapped.
+pub fn munmap_range(start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> isize {
     if let Some(current_task) = current_task() {
         let memory_set = &mut current_task.inner_exclusive_access().memory_set;
-        let page_table = &mut memory_set.page_table;
-        if let Some(area) = memory_set
-            .areas
-            .iter_mut()
-            .find(|area| area.vpn_range.get_start() == vpn)
-        {
-            area.unmap_one(page_table, vpn);
-            return 0;
+        if memory_set.munmap(start_vpn, end_vpn) {
+            0
         } else {
-            println!("not found maparea {:?}", vpn);
-            return -1;
+            -1
         }
     } else {
         -1
     }
 }
+
+/// If `vpn` is backed by a COW area in the current task, give it a
+/// private, writable copy (copying the frame if it's still shared with
+/// another address space, or just restoring the write bit if not) and
+/// return `true`. Returns `false` if `vpn` isn't COW-backed at all.
+///
+/// This is the shared implementation behind `trap::handle_cow_page_fault`
+/// (a real store fault) and the kernel-side user-memory accessors in
+/// `mm::page_table` (`copy_to_user`, `translated_refmut`,
+/// `translated_byte_buffer`), which write into user pages directly via
+/// `write_volatile` and so never take a page fault to drive the normal COW
+/// path — they must call this themselves before writing, or they'd corrupt
+/// a frame still shared with another process.
+pub fn ensure_cow_writable(vpn: VirtPageNum) -> bool {
+    let Some(task) = current_task() else {
+        return false;
+    };
+    let mut inner = task.inner_exclusive_access();
+    let is_cow = inner.memory_set.areas.iter().any(|area| {
+        area.cow && area.vpn_range.get_start().0 <= vpn.0 && vpn.0 < area.vpn_range.get_end().0
+    });
+    if !is_cow {
+        return false;
+    }
+    let Some(pte) = inner.memory_set.page_table.find_pte(vpn) else {
+        return false;
+    };
+    let old_ppn = pte.ppn();
+    if crate::mm::frame_ref_count(old_ppn) <= 1 {
+        // nobody else shares this frame any more: writing in place is safe
+        pte.set_write();
+        return true;
+    }
+    let new_frame = crate::mm::frame_alloc().unwrap();
+    new_frame
+        .ppn
+        .get_bytes_array()
+        .copy_from_slice(old_ppn.get_bytes_array());
+    pte.set_ppn(new_frame.ppn);
+    pte.set_write();
+    // `pte`'s borrow of `page_table` ends here (its last use was above),
+    // so `areas` can be borrowed mutably again to hand the new frame over
+    // to the area that owns this mapping, dropping (and thereby releasing
+    // our share of) the old frame it previously held here
+    let area = inner
+        .memory_set
+        .areas
+        .iter_mut()
+        .find(|area| {
+            area.cow && area.vpn_range.get_start().0 <= vpn.0 && vpn.0 < area.vpn_range.get_end().0
+        })
+        .unwrap();
+    area.replace_frame(vpn, new_frame);
+    true
+}
+
+/// Mark `signum` pending on the task identified by `pid`, used by
+/// `sys_kill`. Fails with `-1` if `signum` is out of range or `pid` does
+/// not name a live task.
+pub fn kill_task(pid: usize, signum: usize) -> isize {
+    if signum >= MAX_SIG {
+        return -1;
+    }
+    let Some(task) = pid2task(pid) else {
+        return -1;
+    };
+    task.inner_exclusive_access().add_signal(signum);
+    0
+}
+
+/// Install a new handler for `signum` on the current task, returning the
+/// previously installed one. Fails with `None` if `signum` is out of
+/// range or is [`SIGKILL`], which can never be caught.
+/// Install `action` as the handler for `signum`, returning the one it
+/// replaces. `action` may be `None` to query the current handler without
+/// installing a new one, e.g. for `sigaction`'s null-`action` case.
+pub fn set_current_task_signal_action(
+    signum: usize,
+    action: Option<SignalAction>,
+) -> Option<SignalAction> {
+    if signum >= MAX_SIG || signum == SIGKILL {
+        return None;
+    }
+    let current_task = current_task()?;
+    let mut inner = current_task.inner_exclusive_access();
+    let old_action = inner.signal_actions[signum];
+    if let Some(action) = action {
+        inner.signal_actions[signum] = action;
+    }
+    Some(old_action)
+}
+
+/// If the current task has an unblocked signal pending, act on it before
+/// returning to user mode: a signal with no handler installed (always the
+/// case for [`SIGKILL`]) kills the task outright, otherwise the trap context is saved
+/// aside, `sepc`/`a0` are set up to run the handler, and delivery of
+/// further signals is held off until `sys_sigreturn` restores it.
+pub fn check_pending_signals() {
+    let Some(task) = current_task() else {
+        return;
+    };
+    let signum = {
+        let inner = task.inner_exclusive_access();
+        inner.next_deliverable_signal()
+    };
+    let Some(signum) = signum else {
+        return;
+    };
+    let action = task.inner_exclusive_access().signal_actions[signum];
+    if action.handler == 0 {
+        // no handler installed: fall back to the default action, which
+        // for every signal this kernel knows about is to kill the task
+        drop(task);
+        exit_current_and_run_next(-(signum as i32));
+        return;
+    }
+    let mut inner = task.inner_exclusive_access();
+    inner.pending_signals &= !(1 << signum);
+    // `handling_signal` alone blocks further delivery until sys_sigreturn;
+    // `action.mask` additionally widens signal_mask for the handler's
+    // duration, restored from `signal_mask_backup` on return
+    inner.handling_signal = signum as isize;
+    inner.signal_mask_backup = inner.signal_mask;
+    inner.signal_mask |= action.mask;
+    let trap_cx = inner.get_trap_cx();
+    inner.trap_ctx_backup = Some(*trap_cx);
+    trap_cx.x[10] = signum;
+    trap_cx.sepc = action.handler;
+}
+
+/// Restore the trap context saved by `check_pending_signals`, called by
+/// `sys_sigreturn` once a signal handler has finished running. Returns
+/// `-1` if no handler is currently being run.
+pub fn sigreturn() -> isize {
+    let Some(task) = current_task() else {
+        return -1;
+    };
+    let mut inner = task.inner_exclusive_access();
+    let Some(backup) = inner.trap_ctx_backup.take() else {
+        return -1;
+    };
+    inner.handling_signal = -1;
+    inner.signal_mask = inner.signal_mask_backup;
+    *inner.get_trap_cx() = backup;
+    // trap_handler writes sys_sigreturn's return value into a0, so return
+    // the restored context's own a0 to leave the pre-signal value in place
+    inner.get_trap_cx().x[10] as isize
+}
+
@@ -0,0 +1,32 @@
+//! A minimal signal subsystem, modelled on the per-task signal state used
+//! by the starnix task code: a pending bitmask, a blocked mask, and a table
+//! of installed handlers.
+
+/// Highest signal number (exclusive) this kernel tracks; signal `n` is
+/// represented by bit `n` of the pending/blocked masks.
+pub const MAX_SIG: usize = 32;
+
+/// The only signal this kernel gives special treatment: `sys_sigaction`
+/// refuses to install a handler for it, so it always takes the default
+/// action of killing the task, matching the traditional uncatchable
+/// `SIGKILL`.
+pub const SIGKILL: usize = 9;
+
+/// A handler installed via `sys_sigaction`: the user-space entry point to
+/// jump to, and the mask to apply (on top of the task's existing blocked
+/// mask) while that handler runs.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct SignalAction {
+    pub handler: usize,
+    pub mask: u32,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self {
+            handler: 0,
+            mask: 0,
+        }
+    }
+}
@@ -0,0 +1,414 @@
+//! Types related to task management & Functions for completely changing TCB
+use super::id::{kstack_alloc, pid_alloc, KernelStack, PidHandle};
+use super::manager::insert_into_pid2task;
+use super::signal::{SignalAction, MAX_SIG};
+use super::TaskContext;
+use crate::config::{MAX_SYSCALL_NUM, TRAP_CONTEXT};
+use crate::fs::{File, Stdin, Stdout};
+use crate::mm::{MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::sync::UPSafeCell;
+use crate::task::TaskStatus;
+use crate::trap::{trap_handler, TrapContext};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefMut;
+
+/// Initial priority assigned to every freshly created task.
+pub const INIT_PRIORITY: isize = 16;
+/// Lower bound accepted by `sys_set_priority`; it keeps `priority` positive
+/// and caps a task's stride at `BIG_STRIDE / MIN_PRIORITY`.
+pub const MIN_PRIORITY: isize = 2;
+/// The stride increment a task accrues per scheduling round is
+/// `BIG_STRIDE / priority`; picked so that `stride` never exceeds half of
+/// `u64::MAX` as long as `priority >= MIN_PRIORITY`.
+pub const BIG_STRIDE: u64 = 0x10000;
+
+/// Task information recorded for `sys_task_info`.
+#[derive(Clone)]
+pub struct TaskInfo {
+    /// Task status in it's life cycle
+    pub status: TaskStatus,
+    /// The numbers of syscall called by task
+    pub syscall_times: [u32; MAX_SYSCALL_NUM],
+    /// Cumulative microseconds spent inside each syscall, sampled at
+    /// dispatch entry/exit; index matches `syscall_times`
+    pub syscall_total_us: [usize; MAX_SYSCALL_NUM],
+    /// Total running time of task
+    pub time: usize,
+}
+
+impl TaskInfo {
+    /// A fresh, all-zero `TaskInfo` for a newly created task.
+    pub fn zero_init() -> Self {
+        Self {
+            status: TaskStatus::Ready,
+            syscall_times: [0; MAX_SYSCALL_NUM],
+            syscall_total_us: [0; MAX_SYSCALL_NUM],
+            time: 0,
+        }
+    }
+}
+
+/// Task control block structure
+///
+/// Directly save the contents that will not change during running
+pub struct TaskControlBlock {
+    // immutable
+    /// Process identifier
+    pub pid: PidHandle,
+    /// Kernel stack corresponding to PID
+    pub kernel_stack: KernelStack,
+    /// Mutable inner members wrapped by `UPSafeCell`
+    inner: UPSafeCell<TaskControlBlockInner>,
+}
+
+pub struct TaskControlBlockInner {
+    /// The physical page number of the frame where the trap context is placed
+    pub trap_cx_ppn: PhysPageNum,
+    /// Application data can only appear in areas
+    /// below `base_size`
+    pub base_size: usize,
+    /// Save task context
+    pub task_cx: TaskContext,
+    /// Maintain the execution status of the current process
+    pub task_status: TaskStatus,
+    /// Application address space
+    pub memory_set: MemorySet,
+    /// Parent process of the current process.
+    /// Weak will not affect the reference count of the parent
+    pub parent: Option<Weak<TaskControlBlock>>,
+    /// A vector containing TCBs of all child processes of the current process
+    pub children: Vec<Arc<TaskControlBlock>>,
+    /// It is set when active exit or execution error occurs
+    pub exit_code: i32,
+    /// File descriptor table
+    pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    /// Collected per-task stats surfaced through `sys_task_info`
+    pub task_info: TaskInfo,
+    /// Timestamp (ms) of the first syscall made by this task
+    pub start_time: usize,
+    /// Timestamp (ms) of the most recent syscall made by this task
+    pub end_time: usize,
+    /// Scheduling priority; see [`INIT_PRIORITY`] and [`MIN_PRIORITY`]
+    pub priority: isize,
+    /// Stride increment accrued per scheduling round, `BIG_STRIDE / priority`
+    pub stride: u64,
+    /// Cumulative stride "distance" travelled by this task so far; wraps on
+    /// overflow and is compared with the signed-difference trick
+    pub pass: u64,
+    /// Bitmask of signals delivered but not yet handled; bit `n` is signal `n`
+    pub pending_signals: u32,
+    /// Bitmask of signals currently blocked from delivery
+    pub signal_mask: u32,
+    /// Signal number currently being handled, or `-1` if none. Delivery of
+    /// further signals is held off while this is set, until `sys_sigreturn`
+    /// restores `trap_ctx_backup` and clears it.
+    pub handling_signal: isize,
+    /// Installed handler for each signal number
+    pub signal_actions: [SignalAction; MAX_SIG],
+    /// The trap context saved when a handler was entered, restored by
+    /// `sys_sigreturn`
+    pub trap_ctx_backup: Option<TrapContext>,
+    /// `signal_mask` as it was before the currently-running handler widened
+    /// it with its own `SignalAction::mask`; restored by `sys_sigreturn`
+    /// alongside `trap_ctx_backup`
+    pub signal_mask_backup: u32,
+}
+
+impl TaskControlBlockInner {
+    pub fn get_trap_cx(&self) -> &'static mut TrapContext {
+        self.trap_cx_ppn.get_mut()
+    }
+    pub fn get_user_token(&self) -> usize {
+        self.memory_set.token()
+    }
+    fn get_status(&self) -> TaskStatus {
+        self.task_status
+    }
+    pub fn is_zombie(&self) -> bool {
+        self.get_status() == TaskStatus::Zombie
+    }
+    pub fn alloc_fd(&mut self) -> usize {
+        if let Some(fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
+            fd
+        } else {
+            self.fd_table.push(None);
+            self.fd_table.len() - 1
+        }
+    }
+    /// Recompute `stride` from `priority`; called whenever `priority` changes.
+    pub fn update_stride(&mut self) {
+        self.stride = BIG_STRIDE / self.priority as u64;
+    }
+    /// Mark `signum` as pending delivery.
+    pub fn add_signal(&mut self, signum: usize) {
+        self.pending_signals |= 1 << signum;
+    }
+    /// The lowest-numbered signal that is both pending and unblocked, if
+    /// any, which is the one `check_pending_signals` should act on next.
+    pub fn next_deliverable_signal(&self) -> Option<usize> {
+        if self.handling_signal != -1 {
+            // a handler is already running; wait for sys_sigreturn
+            return None;
+        }
+        let deliverable = self.pending_signals & !self.signal_mask;
+        if deliverable == 0 {
+            None
+        } else {
+            Some(deliverable.trailing_zeros() as usize)
+        }
+    }
+}
+
+impl TaskControlBlock {
+    pub fn inner_exclusive_access(&self) -> RefMut<'_, TaskControlBlockInner> {
+        self.inner.exclusive_access()
+    }
+    pub fn getpid(&self) -> usize {
+        self.pid.0
+    }
+    /// Create a new process, used by init process `initproc`
+    pub fn new(elf_data: &[u8]) -> Self {
+        // memory_set with elf program headers/trampoline/trap context/user stack
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn();
+        let task_status = TaskStatus::Ready;
+        // map a kernel-stack in kernel space
+        let pid_handle = pid_alloc();
+        let kernel_stack = kstack_alloc();
+        let kernel_stack_top = kernel_stack.get_top();
+        let task_control_block = Self {
+            pid: pid_handle,
+            kernel_stack,
+            inner: unsafe {
+                UPSafeCell::new(TaskControlBlockInner {
+                    trap_cx_ppn,
+                    base_size: user_sp,
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status,
+                    memory_set,
+                    parent: None,
+                    children: Vec::new(),
+                    exit_code: 0,
+                    fd_table: vec![
+                        // 0 -> stdin
+                        Some(Arc::new(Stdin)),
+                        // 1 -> stdout
+                        Some(Arc::new(Stdout)),
+                        // 2 -> stderr
+                        Some(Arc::new(Stdout)),
+                    ],
+                    task_info: TaskInfo::zero_init(),
+                    start_time: 0,
+                    end_time: 0,
+                    priority: INIT_PRIORITY,
+                    stride: BIG_STRIDE / INIT_PRIORITY as u64,
+                    pass: 0,
+                    pending_signals: 0,
+                    signal_mask: 0,
+                    handling_signal: -1,
+                    signal_actions: [SignalAction::default(); MAX_SIG],
+                    trap_ctx_backup: None,
+                    signal_mask_backup: 0,
+                })
+            },
+        };
+        // prepare TrapContext in user space
+        let trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            kernel_stack_top,
+            trap_handler as usize,
+        );
+        task_control_block
+    }
+
+    /// Load a new elf to replace the current process, used by `sys_exec`
+    pub fn exec(&self, elf_data: &[u8]) {
+        // memory_set with elf program headers/trampoline/trap context/user stack
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn();
+
+        // **** access current TCB exclusively
+        let mut inner = self.inner_exclusive_access();
+        // substitute memory_set
+        inner.memory_set = memory_set;
+        // update trap_cx ppn
+        inner.trap_cx_ppn = trap_cx_ppn;
+        // initialize base_size
+        inner.base_size = user_sp;
+        // initialize trap_cx
+        let trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            self.kernel_stack.get_top(),
+            trap_handler as usize,
+        );
+        *inner.get_trap_cx() = trap_cx;
+        // **** release inner automatically
+    }
+
+    /// parent process fork the child process
+    pub fn fork(self: &Arc<TaskControlBlock>) -> Arc<TaskControlBlock> {
+        // ---- access parent PCB exclusively
+        let mut parent_inner = self.inner_exclusive_access();
+        // copy-on-write user space: shares data frames with the parent
+        // instead of duplicating them eagerly (only the trap context page
+        // is copied right away)
+        let memory_set = MemorySet::from_existing_user(&mut parent_inner.memory_set);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn();
+        // alloc a pid and a kernel stack in kernel space
+        let pid_handle = pid_alloc();
+        let kernel_stack = kstack_alloc();
+        let kernel_stack_top = kernel_stack.get_top();
+        // copy fd table
+        let mut new_fd_table: Vec<Option<Arc<dyn File + Send + Sync>>> = Vec::new();
+        for fd in parent_inner.fd_table.iter() {
+            if let Some(file) = fd {
+                new_fd_table.push(Some(file.clone()));
+            } else {
+                new_fd_table.push(None);
+            }
+        }
+        let task_control_block = Arc::new(TaskControlBlock {
+            pid: pid_handle,
+            kernel_stack,
+            inner: unsafe {
+                UPSafeCell::new(TaskControlBlockInner {
+                    trap_cx_ppn,
+                    base_size: parent_inner.base_size,
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status: TaskStatus::Ready,
+                    memory_set,
+                    parent: Some(Arc::downgrade(self)),
+                    children: Vec::new(),
+                    exit_code: 0,
+                    fd_table: new_fd_table,
+                    task_info: TaskInfo::zero_init(),
+                    start_time: 0,
+                    end_time: 0,
+                    priority: parent_inner.priority,
+                    stride: parent_inner.stride,
+                    pass: parent_inner.pass,
+                    pending_signals: 0,
+                    signal_mask: parent_inner.signal_mask,
+                    handling_signal: -1,
+                    signal_actions: parent_inner.signal_actions,
+                    trap_ctx_backup: None,
+                    signal_mask_backup: 0,
+                })
+            },
+        });
+        // add child
+        parent_inner.children.push(task_control_block.clone());
+        insert_into_pid2task(task_control_block.getpid(), task_control_block.clone());
+        // modify kernel_sp in trap_cx
+        // **** access child PCB exclusively
+        let trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
+        trap_cx.kernel_sp = kernel_stack_top;
+        // return
+        task_control_block
+        // **** release child PCB
+        // ---- release parent PCB
+    }
+
+    /// Spawn a brand-new child process directly from an ELF image.
+    ///
+    /// Unlike `fork` + `exec`, this never copies the parent's address space
+    /// only to discard it: the child's `MemorySet` is built straight from
+    /// `elf_data` via `MemorySet::from_elf`. Fd table and priority are
+    /// inherited from the parent, and the new task is linked in as a child
+    /// exactly like `fork` does.
+    pub fn spawn(self: &Arc<TaskControlBlock>, elf_data: &[u8]) -> Arc<TaskControlBlock> {
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn();
+        let pid_handle = pid_alloc();
+        let kernel_stack = kstack_alloc();
+        let kernel_stack_top = kernel_stack.get_top();
+
+        // ---- access parent PCB exclusively
+        let mut parent_inner = self.inner_exclusive_access();
+        let mut new_fd_table: Vec<Option<Arc<dyn File + Send + Sync>>> = Vec::new();
+        for fd in parent_inner.fd_table.iter() {
+            if let Some(file) = fd {
+                new_fd_table.push(Some(file.clone()));
+            } else {
+                new_fd_table.push(None);
+            }
+        }
+        let task_control_block = Arc::new(TaskControlBlock {
+            pid: pid_handle,
+            kernel_stack,
+            inner: unsafe {
+                UPSafeCell::new(TaskControlBlockInner {
+                    trap_cx_ppn,
+                    base_size: user_sp,
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status: TaskStatus::Ready,
+                    memory_set,
+                    parent: Some(Arc::downgrade(self)),
+                    children: Vec::new(),
+                    exit_code: 0,
+                    fd_table: new_fd_table,
+                    task_info: TaskInfo::zero_init(),
+                    start_time: 0,
+                    end_time: 0,
+                    priority: parent_inner.priority,
+                    stride: parent_inner.stride,
+                    pass: parent_inner.pass,
+                    pending_signals: 0,
+                    signal_mask: 0,
+                    handling_signal: -1,
+                    signal_actions: [SignalAction::default(); MAX_SIG],
+                    trap_ctx_backup: None,
+                    signal_mask_backup: 0,
+                })
+            },
+        });
+        parent_inner.children.push(task_control_block.clone());
+        insert_into_pid2task(task_control_block.getpid(), task_control_block.clone());
+        drop(parent_inner);
+        // ---- release parent PCB
+
+        let trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            kernel_stack_top,
+            trap_handler as usize,
+        );
+        task_control_block
+    }
+
+    /// change the program break, see `MemorySet::change_program_brk`
+    pub fn change_program_brk(&self, size: i32) -> Option<usize> {
+        let mut inner = self.inner_exclusive_access();
+        inner.memory_set.change_program_brk(size)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+/// task status: Ready, Running, Zombie
+pub enum TaskStatus {
+    Ready,
+    Running,
+    Zombie,
+}
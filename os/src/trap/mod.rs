@@ -0,0 +1,136 @@
+//! Trap handling functionality
+mod context;
+
+use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
+use crate::mm::VirtAddr;
+use crate::syscall::syscall;
+use crate::task::{
+    check_pending_signals, current_trap_cx, current_user_token, ensure_cow_writable,
+    exit_current_and_run_next, record_current_task_syscall_time, suspend_current_and_run_next,
+};
+use crate::timer::{get_time_us, set_next_trigger};
+use core::arch::{asm, global_asm};
+pub use context::TrapContext;
+use riscv::register::{
+    mtvec::TrapMode,
+    scause::{self, Exception, Interrupt, Trap},
+    sie, stval, stvec,
+};
+
+global_asm!(include_str!("trap.S"));
+
+pub fn init() {
+    set_kernel_trap_entry();
+}
+
+fn set_kernel_trap_entry() {
+    extern "C" {
+        fn __alltraps();
+        fn __alltraps_k();
+    }
+    let __alltraps_k_va = __alltraps_k as usize - __alltraps as usize + TRAMPOLINE;
+    unsafe {
+        stvec::write(__alltraps_k_va, TrapMode::Direct);
+    }
+}
+
+fn set_user_trap_entry() {
+    unsafe {
+        stvec::write(TRAMPOLINE, TrapMode::Direct);
+    }
+}
+
+pub fn enable_timer_interrupt() {
+    unsafe {
+        sie::set_stimer();
+    }
+}
+
+/// Attempt to satisfy a store-page-fault on a copy-on-write page. Returns
+/// `false` if `va` is not actually backed by a COW area, meaning this
+/// really is a fault the kernel should kill the task over.
+///
+/// The actual COW-breaking logic lives in [`ensure_cow_writable`], shared
+/// with the kernel-side user-memory accessors in `mm::page_table`, which
+/// must break COW themselves before writing through a user pointer rather
+/// than relying on a store fault that a kernel-side `write_volatile` will
+/// never trigger.
+fn handle_cow_page_fault(va: usize) -> bool {
+    ensure_cow_writable(VirtAddr::from(va).floor())
+}
+
+#[no_mangle]
+pub fn trap_handler() -> ! {
+    set_kernel_trap_entry();
+    let scause = scause::read();
+    let stval = stval::read();
+    match scause.cause() {
+        Trap::Exception(Exception::UserEnvCall) => {
+            let mut cx = current_trap_cx();
+            cx.sepc += 4;
+            let syscall_id = cx.x[17];
+            let entry_us = get_time_us();
+            let result = syscall(syscall_id, [cx.x[10], cx.x[11], cx.x[12]]) as usize;
+            record_current_task_syscall_time(syscall_id, get_time_us() - entry_us);
+            cx = current_trap_cx();
+            cx.x[10] = result;
+        }
+        Trap::Exception(Exception::StorePageFault) | Trap::Exception(Exception::StoreFault) => {
+            if !handle_cow_page_fault(stval) {
+                println!(
+                    "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.",
+                    scause.cause(),
+                    stval,
+                    current_trap_cx().sepc,
+                );
+                exit_current_and_run_next(-2);
+            }
+        }
+        Trap::Exception(Exception::IllegalInstruction) => {
+            println!("[kernel] IllegalInstruction in application, core dumped.");
+            exit_current_and_run_next(-3);
+        }
+        Trap::Interrupt(Interrupt::SupervisorTimer) => {
+            set_next_trigger();
+            suspend_current_and_run_next();
+        }
+        _ => {
+            panic!(
+                "Unsupported trap {:?}, stval = {:#x}!",
+                scause.cause(),
+                stval
+            );
+        }
+    }
+    trap_return();
+}
+
+#[no_mangle]
+pub fn trap_return() -> ! {
+    // deliver any signal that arrived while we were in the kernel before
+    // handing control back to user mode
+    check_pending_signals();
+    set_user_trap_entry();
+    let trap_cx_ptr = TRAP_CONTEXT;
+    let user_satp = current_user_token();
+    extern "C" {
+        fn __alltraps();
+        fn __restore();
+    }
+    let restore_va = __restore as usize - __alltraps as usize + TRAMPOLINE;
+    unsafe {
+        asm!(
+            "fence.i",
+            "jr {restore_va}",
+            restore_va = in(reg) restore_va,
+            in("a0") trap_cx_ptr,
+            in("a1") user_satp,
+            options(noreturn)
+        );
+    }
+}
+
+#[no_mangle]
+pub fn trap_from_kernel() -> ! {
+    panic!("a trap from kernel!");
+}